@@ -1,20 +1,170 @@
-use std::{fs, path::Path};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
 
-use crate::Polycube;
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
 
+use crate::{canonical_key, unpack_bits, BitKey, Polycube};
+
+/// Magic bytes identifying the versioned, compressed cache format. Caches
+/// written before this format existed have no header at all, so its absence
+/// is how we detect them.
+const MAGIC: &[u8; 4] = b"CUBE";
+const VERSION: u8 = 1;
+
+// The wrapped errors are only ever read through the derived `Debug` impl,
+// when an `.unwrap()` panics on a cache error -- that doesn't count as a
+// read for dead-code analysis, hence the blanket allow.
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum Error {
     Bincode(Box<bincode::ErrorKind>),
     Io(std::io::Error),
+    /// The file doesn't start with a magic/version header we recognise.
+    UnsupportedFormat,
 }
 
 pub fn get_cache(path: &Path) -> Result<Vec<Polycube>, Error> {
-    let bin = fs::read(path).map_err(Error::Io)?;
-    let data: Vec<Polycube> = bincode::deserialize(&bin).map_err(Error::Bincode)?;
-    Ok(data)
+    match CacheReader::open(path) {
+        Ok(reader) => reader.map(|key| key.map(|key| unpack_bits(&key))).collect(),
+        Err(Error::UnsupportedFormat) => {
+            // Caches from before the versioned format stored a single
+            // bincode-serialized `Vec<Polycube>` with no header at all.
+            let bin = fs::read(path).map_err(Error::Io)?;
+            bincode::deserialize(&bin).map_err(Error::Bincode)
+        }
+        Err(e) => Err(e),
+    }
 }
 
 pub fn save_cache(path: &Path, data: &Vec<Polycube>) -> Result<(), Error> {
-    let bin = bincode::serialize(&data).map_err(Error::Bincode)?;
-    fs::write(path, bin).map_err(Error::Io)
+    let mut writer = CacheWriter::create(path)?;
+    for polycube in data {
+        writer.append(&canonical_key(polycube))?;
+    }
+    writer.flush()
+}
+
+/// Reads canonical bit-packed keys out of a cache file one record at a time.
+///
+/// The file starts with a magic/version header, followed by an LZ4 frame
+/// containing length-prefixed, bincode-encoded `BitKey` records. Polycube
+/// grids compress extremely well, so this shrinks cache files several-fold
+/// over storing raw `ndarray` buffers.
+pub struct CacheReader {
+    reader: FrameDecoder<BufReader<File>>,
+}
+
+impl CacheReader {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut file = File::open(path).map_err(Error::Io)?;
+
+        let mut header = [0u8; MAGIC.len() + 1];
+        file.read_exact(&mut header)
+            .map_err(|_| Error::UnsupportedFormat)?;
+        if &header[..MAGIC.len()] != MAGIC || header[MAGIC.len()] != VERSION {
+            return Err(Error::UnsupportedFormat);
+        }
+
+        Ok(Self {
+            reader: FrameDecoder::new(BufReader::new(file)),
+        })
+    }
+}
+
+impl Iterator for CacheReader {
+    type Item = Result<BitKey, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut len_buf = [0u8; 8];
+        match self.reader.read_exact(&mut len_buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(Error::Io(e))),
+        }
+        let len = u64::from_le_bytes(len_buf) as usize;
+
+        let mut record = vec![0u8; len];
+        if let Err(e) = self.reader.read_exact(&mut record) {
+            return Some(Err(Error::Io(e)));
+        }
+
+        Some(bincode::deserialize(&record).map_err(Error::Bincode))
+    }
+}
+
+/// Appends polycubes to a cache file one record at a time, storing only
+/// their canonical bit-packed key and streaming everything through an LZ4
+/// frame encoder, so a producer never has to buffer a whole level before
+/// writing it out.
+pub struct CacheWriter {
+    writer: Option<FrameEncoder<BufWriter<File>>>,
+}
+
+impl CacheWriter {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        let mut file = File::create(path).map_err(Error::Io)?;
+        file.write_all(MAGIC).map_err(Error::Io)?;
+        file.write_all(&[VERSION]).map_err(Error::Io)?;
+
+        Ok(Self {
+            writer: Some(FrameEncoder::new(BufWriter::new(file))),
+        })
+    }
+
+    pub fn append(&mut self, key: &BitKey) -> Result<(), Error> {
+        let record = bincode::serialize(key).map_err(Error::Bincode)?;
+        let writer = self.writer.as_mut().expect("append after flush");
+        writer
+            .write_all(&(record.len() as u64).to_le_bytes())
+            .map_err(Error::Io)?;
+        writer.write_all(&record).map_err(Error::Io)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if let Some(writer) = self.writer.take() {
+            writer
+                .finish()
+                .map_err(|e| Error::Io(io::Error::other(e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[test]
+fn test_cache_round_trip() {
+    let path = std::env::temp_dir().join("cubes_cache_round_trip_test.bin");
+
+    let shapes = vec![
+        ndarray::Array::<u8, _>::ones((1, 1, 1)),
+        ndarray::Array::<u8, _>::ones((2, 1, 1)),
+    ];
+    save_cache(&path, &shapes).unwrap();
+    let read = get_cache(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let expected: std::collections::HashSet<BitKey> = shapes.iter().map(canonical_key).collect();
+    let actual: std::collections::HashSet<BitKey> = read.iter().map(canonical_key).collect();
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn test_cache_legacy_format_fallback() {
+    let path = std::env::temp_dir().join("cubes_cache_legacy_test.bin");
+
+    let shapes = vec![ndarray::Array::<u8, _>::ones((1, 1, 1))];
+    std::fs::write(&path, bincode::serialize(&shapes).unwrap()).unwrap();
+
+    let read = get_cache(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(read, shapes);
 }
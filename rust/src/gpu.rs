@@ -0,0 +1,348 @@
+//! `wgpu`-backed canonicalization: computes every candidate's canonical
+//! `BitKey` with one compute dispatch per batch instead of a per-candidate
+//! CPU rotation sweep.
+//!
+//! Each candidate is cropped to its bounding box on the host (same as the
+//! CPU path) and packed into a fixed-size grid. Because the box is already
+//! tight, applying any of the 24 axis permutation/flip transforms to it
+//! yields another tight box, so the shader never needs to re-crop — it just
+//! evaluates all 24 transforms and keeps the lexicographically smallest one.
+
+use bytemuck::{Pod, Zeroable};
+use std::sync::OnceLock;
+use wgpu::util::DeviceExt;
+
+use crate::{canonical_key, crop_cube, BitKey, PackedBits, Polycube};
+
+/// Largest single extent a candidate may have in any axis to use the GPU
+/// backend. Candidates are cropped first, so this bounds the cropped shape,
+/// not the level `n` itself.
+const MAX_DIM: usize = 8;
+const MAX_CELLS: usize = MAX_DIM * MAX_DIM * MAX_DIM;
+const MAX_WORDS: usize = MAX_CELLS / 32;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct PackedCandidate {
+    dims: [u32; 3],
+    _pad: u32,
+    words: [u32; MAX_WORDS],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct RotationDescriptor {
+    perm: [u32; 3],
+    flip: [u32; 3],
+    _pad: [u32; 2],
+}
+
+/// Computes the canonical `BitKey` of every candidate, dispatching the ones
+/// that fit the GPU's fixed-size buffers in one batch and falling back to
+/// the CPU rotation sweep for any that don't.
+///
+/// A maximally-elongated "rod" polycube exists at every level and can exceed
+/// `MAX_DIM` well within the large-`n` range this backend targets, so
+/// oversized candidates are a real, expected case rather than a caller error.
+pub(crate) fn canonical_keys(candidates: &[Polycube]) -> Vec<BitKey> {
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+
+    let mut keys: Vec<Option<BitKey>> = vec![None; candidates.len()];
+    let mut gpu_indices = Vec::new();
+    let mut packed = Vec::new();
+
+    for (i, candidate) in candidates.iter().enumerate() {
+        let cropped = crop_cube(candidate);
+        if fits_gpu(&cropped) {
+            gpu_indices.push(i);
+            packed.push(pack_for_gpu(&cropped));
+        } else {
+            keys[i] = Some(canonical_key(candidate));
+        }
+    }
+
+    if !packed.is_empty() {
+        let results = GpuContext::get().canonicalize_batch(&packed);
+        for (i, result) in gpu_indices.into_iter().zip(results) {
+            keys[i] = Some(unpack_from_gpu(&result));
+        }
+    }
+
+    keys.into_iter()
+        .map(|key| key.expect("every candidate is canonicalized by either the GPU or CPU path"))
+        .collect()
+}
+
+/// Whether a cropped candidate's extents fit the GPU backend's fixed-size
+/// packed representation.
+fn fits_gpu(cropped: &Polycube) -> bool {
+    cropped.shape().iter().all(|&d| d <= MAX_DIM)
+}
+
+fn pack_for_gpu(cropped: &Polycube) -> PackedCandidate {
+    let shape = cropped.shape();
+    debug_assert!(fits_gpu(cropped), "caller must filter oversized candidates");
+
+    let mut words = [0u32; MAX_WORDS];
+    for (i, &v) in cropped.iter().enumerate() {
+        if v == 1 {
+            words[i / 32] |= 1 << (i % 32);
+        }
+    }
+
+    PackedCandidate {
+        dims: [shape[0] as u32, shape[1] as u32, shape[2] as u32],
+        _pad: 0,
+        words,
+    }
+}
+
+fn unpack_from_gpu(result: &PackedCandidate) -> BitKey {
+    let cells = (result.dims[0] * result.dims[1] * result.dims[2]) as usize;
+
+    let mut bits: PackedBits = smallvec::smallvec![0u8; cells.div_ceil(8)];
+    for i in 0..cells {
+        if result.words[i / 32] & (1 << (i % 32)) != 0 {
+            bits[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    BitKey {
+        dims: [
+            result.dims[0] as u16,
+            result.dims[1] as u16,
+            result.dims[2] as u16,
+        ],
+        bits,
+    }
+}
+
+/// The 24 proper rotations of a cube, expressed as the axis permutation and
+/// per-axis flip to apply when reading from the original (unrotated) grid.
+///
+/// Generated from all 48 permutation/flip combinations by keeping only the
+/// ones with determinant +1 — the orientation-preserving half, which is
+/// exactly the rotation group.
+fn proper_rotations() -> Vec<RotationDescriptor> {
+    let mut rotations = Vec::with_capacity(24);
+    for perm in crate::AXIS_PERMS {
+        let perm_sign = crate::permutation_sign(perm);
+        for flip_bits in 0u32..8 {
+            let flip = [flip_bits & 1, (flip_bits >> 1) & 1, (flip_bits >> 2) & 1];
+            let flip_sign: i32 = flip.iter().map(|&f| if f == 1 { -1 } else { 1 }).product();
+
+            if perm_sign * flip_sign == 1 {
+                rotations.push(RotationDescriptor {
+                    perm: [perm[0] as u32, perm[1] as u32, perm[2] as u32],
+                    flip,
+                    _pad: [0, 0],
+                });
+            }
+        }
+    }
+
+    debug_assert_eq!(rotations.len(), 24);
+    rotations
+}
+
+#[test]
+fn test_proper_rotations_are_24_distinct_transforms() {
+    let rotations = proper_rotations();
+    assert_eq!(rotations.len(), 24);
+
+    let distinct: std::collections::HashSet<([u32; 3], [u32; 3])> =
+        rotations.iter().map(|r| (r.perm, r.flip)).collect();
+    assert_eq!(distinct.len(), 24);
+}
+
+#[test]
+fn test_pack_unpack_gpu_round_trip() {
+    let mut cube = crate::Polycube::zeros((2, 3, 1));
+    cube[[0, 0, 0]] = 1;
+    cube[[1, 2, 0]] = 1;
+    cube[[0, 2, 0]] = 1;
+
+    let cropped = crop_cube(&cube);
+    assert!(fits_gpu(&cropped));
+
+    let packed = pack_for_gpu(&cropped);
+    let unpacked = unpack_from_gpu(&packed);
+
+    assert_eq!(unpacked, crate::pack_bits(&cropped));
+}
+
+#[test]
+fn test_fits_gpu_rejects_oversized_candidates() {
+    let rod = crate::Polycube::ones((MAX_DIM + 1, 1, 1));
+    assert!(!fits_gpu(&rod));
+
+    let cube = crate::Polycube::ones((MAX_DIM, MAX_DIM, MAX_DIM));
+    assert!(fits_gpu(&cube));
+}
+
+#[test]
+fn test_canonicalize_batch_matches_cpu_canonical_key() {
+    let mut l_tromino = Polycube::zeros((2, 2, 1));
+    l_tromino[[0, 0, 0]] = 1;
+    l_tromino[[1, 0, 0]] = 1;
+    l_tromino[[1, 1, 0]] = 1;
+
+    let mut asymmetric = Polycube::zeros((3, 3, 2));
+    for (x, y, z) in [
+        (1, 1, 1),
+        (0, 1, 1),
+        (0, 2, 1),
+        (0, 2, 0),
+        (2, 1, 1),
+        (0, 0, 1),
+    ] {
+        asymmetric[[x, y, z]] = 1;
+    }
+
+    let single = Polycube::ones((1, 1, 1));
+    let rod = Polycube::ones((1, 1, MAX_DIM));
+
+    let candidates = [l_tromino, asymmetric, single, rod];
+    let expected: Vec<BitKey> = candidates.iter().map(canonical_key).collect();
+    let actual = canonical_keys(&candidates);
+
+    assert_eq!(actual, expected);
+}
+
+struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    rotations_buffer: wgpu::Buffer,
+}
+
+static CONTEXT: OnceLock<GpuContext> = OnceLock::new();
+
+impl GpuContext {
+    fn get() -> &'static GpuContext {
+        CONTEXT.get_or_init(|| pollster::block_on(GpuContext::new()))
+    }
+
+    async fn new() -> Self {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            })
+            .await
+            .expect("no suitable GPU adapter found for the `gpu` backend");
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .await
+            .expect("failed to acquire a GPU device for the `gpu` backend");
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("canonicalize"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/canonicalize.wgsl").into()),
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("canonicalize_pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: "canonicalize",
+            compilation_options: Default::default(),
+        });
+
+        let rotations = proper_rotations();
+        let rotations_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("rotations"),
+            contents: bytemuck::cast_slice(&rotations),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        Self {
+            device,
+            queue,
+            pipeline,
+            rotations_buffer,
+        }
+    }
+
+    fn canonicalize_batch(&self, packed: &[PackedCandidate]) -> Vec<PackedCandidate> {
+        let candidates_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("candidates"),
+                contents: bytemuck::cast_slice(packed),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+
+        let results_size = std::mem::size_of_val(packed) as u64;
+        let results_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("results"),
+            size: results_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size: results_size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = self.pipeline.get_bind_group_layout(0);
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("canonicalize_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: candidates_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.rotations_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: results_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("canonicalize_encoder"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("canonicalize_pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(packed.len().div_ceil(64) as u32, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&results_buffer, 0, &staging_buffer, 0, results_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = staging_buffer.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |res| {
+            let _ = tx.send(res);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        rx.recv()
+            .expect("GPU map_async callback dropped without a response")
+            .expect("failed to map the GPU results buffer for reading");
+
+        let results: Vec<PackedCandidate> =
+            bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging_buffer.unmap();
+
+        results
+    }
+}
@@ -1,10 +1,28 @@
 use std::time::Instant;
 
-use clap::Parser;
-use cubes::generate_polycubes;
+use clap::{Parser, ValueEnum};
+use cubes::{generate_polycubes_iter, Backend};
 
 // TODO: https://nnethercote.github.io/perf-book/title-page.html
 
+/// Which backend to use for candidate canonicalization.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum BackendArg {
+    /// Canonicalize on the CPU.
+    Cpu,
+    /// Canonicalize in batches on the GPU. Requires the `gpu` feature.
+    Gpu,
+}
+
+impl From<BackendArg> for Backend {
+    fn from(arg: BackendArg) -> Self {
+        match arg {
+            BackendArg::Cpu => Backend::Cpu,
+            BackendArg::Gpu => Backend::Gpu,
+        }
+    }
+}
+
 /// Generates all polycubes (combinations of cubes) of size n.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -15,6 +33,10 @@ struct Args {
     /// Cache results to disk
     #[arg(long, default_value_t = false)]
     no_cache: bool,
+
+    /// Backend used to canonicalize candidates
+    #[arg(long, value_enum, default_value = "cpu")]
+    backend: BackendArg,
 }
 
 fn main() {
@@ -23,12 +45,14 @@ fn main() {
     // Start the timer
     let t1_start = Instant::now();
 
-    let all_cubes = generate_polycubes(args.number, !args.no_cache);
+    // Streamed so that counting large n never requires holding every shape in RAM at once,
+    // regardless of which backend canonicalizes the candidates.
+    let count = generate_polycubes_iter(args.number, !args.no_cache, args.backend.into()).count();
 
     // Stop the timer
     let t1_stop = Instant::now();
 
-    println!("Found {} unique polycube(s)", all_cubes.len());
+    println!("Found {} unique polycube(s)", count);
     println!(
         "Elapsed time: {}s",
         t1_stop.duration_since(t1_start).as_secs()